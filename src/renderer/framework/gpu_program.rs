@@ -16,22 +16,306 @@ use crate::{
     },
     utils::log::Log,
 };
-use std::{cell::RefCell, ffi::CString, marker::PhantomData, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    ffi::CString,
+    marker::PhantomData,
+    rc::Rc,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+// Handed out one per linked `GpuProgram`, never reused - lets a stale
+// `UniformLocation` be detected after a `ShaderManager` hot-reload swap.
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+fn next_generation() -> u64 {
+    NEXT_GENERATION.fetch_add(1, Ordering::Relaxed)
+}
 
 pub struct GpuProgram {
     id: GLuint,
+    generation: u64,
     name_buf: RefCell<Vec<u8>>,
+    // Active uniforms reported by the driver right after linking.
+    uniforms: HashMap<Rc<str>, UniformInfo>,
+    // Reverse lookup used only for naming a uniform in a warning message.
+    uniform_names: HashMap<GLint, Rc<str>>,
+    // (generation, location) pairs already warned about, so a uniform
+    // mismatch set every frame doesn't spam the log.
+    warned_uniforms: RefCell<HashSet<(u64, GLint)>>,
     // Force compiler to not implement Send and Sync, because OpenGL is not thread-safe.
     thread_mark: PhantomData<*const u8>,
 }
 
+#[derive(Copy, Clone)]
+struct UniformInfo {
+    location: GLint,
+    gl_type: GLuint,
+    size: GLint,
+}
+
 #[derive(Copy, Clone)]
 pub struct UniformLocation {
     id: GLint,
+    gl_type: GLuint,
+    size: GLint,
+    // The `GpuProgram::generation` this location was resolved against.
+    generation: u64,
+    // Force compiler to not implement Send and Sync, because OpenGL is not thread-safe.
+    thread_mark: PhantomData<*const u8>,
+}
+
+/// Index of a named uniform block, obtained via [`GpuProgram::uniform_block_index`].
+#[derive(Copy, Clone)]
+pub struct UniformBlockIndex {
+    id: GLuint,
     // Force compiler to not implement Send and Sync, because OpenGL is not thread-safe.
     thread_mark: PhantomData<*const u8>,
 }
 
+/// A GPU-side buffer holding one `std140` uniform block.
+pub struct UniformBuffer {
+    id: GLuint,
+    size: usize,
+    // Force compiler to not implement Send and Sync, because OpenGL is not thread-safe.
+    thread_mark: PhantomData<*const u8>,
+}
+
+impl UniformBuffer {
+    pub fn new(size: usize) -> Self {
+        unsafe {
+            let mut id = 0;
+            gl::GenBuffers(1, &mut id);
+            gl::BindBuffer(gl::UNIFORM_BUFFER, id);
+            gl::BufferData(
+                gl::UNIFORM_BUFFER,
+                size as isize,
+                std::ptr::null(),
+                gl::DYNAMIC_DRAW,
+            );
+            gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+            Self {
+                id,
+                size,
+                thread_mark: PhantomData,
+            }
+        }
+    }
+
+    /// Uploads `data` (built with [`Std140Writer`]) to the start of the buffer.
+    pub fn write(&self, data: &[u8]) {
+        assert!(
+            data.len() <= self.size,
+            "uniform buffer overflow: {} bytes does not fit in a {} byte buffer",
+            data.len(),
+            self.size
+        );
+        unsafe {
+            gl::BindBuffer(gl::UNIFORM_BUFFER, self.id);
+            gl::BufferSubData(
+                gl::UNIFORM_BUFFER,
+                0,
+                data.len() as isize,
+                data.as_ptr() as *const _,
+            );
+            gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+        }
+    }
+
+    pub fn bind(&self, binding_point: u32) {
+        unsafe {
+            gl::BindBufferBase(gl::UNIFORM_BUFFER, binding_point, self.id);
+        }
+    }
+}
+
+impl Drop for UniformBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.id);
+        }
+    }
+}
+
+/// Packs values into a `std140`-layout byte buffer for [`UniformBuffer::write`].
+#[derive(Default)]
+pub struct Std140Writer {
+    buffer: Vec<u8>,
+}
+
+impl Std140Writer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn align_to(&mut self, align: usize) {
+        let rem = self.buffer.len() % align;
+        if rem != 0 {
+            self.buffer.resize(self.buffer.len() + (align - rem), 0);
+        }
+    }
+
+    pub fn write_integer(&mut self, value: i32) -> &mut Self {
+        self.align_to(4);
+        self.buffer.extend_from_slice(&value.to_ne_bytes());
+        self
+    }
+
+    pub fn write_float(&mut self, value: f32) -> &mut Self {
+        self.align_to(4);
+        self.buffer.extend_from_slice(&value.to_ne_bytes());
+        self
+    }
+
+    pub fn write_vec2(&mut self, value: Vec2) -> &mut Self {
+        self.align_to(8);
+        self.buffer.extend_from_slice(&value.x.to_ne_bytes());
+        self.buffer.extend_from_slice(&value.y.to_ne_bytes());
+        self
+    }
+
+    pub fn write_vec3(&mut self, value: Vec3) -> &mut Self {
+        self.align_to(16);
+        self.buffer.extend_from_slice(&value.x.to_ne_bytes());
+        self.buffer.extend_from_slice(&value.y.to_ne_bytes());
+        self.buffer.extend_from_slice(&value.z.to_ne_bytes());
+        self
+    }
+
+    pub fn write_vec4(&mut self, value: Vec4) -> &mut Self {
+        self.align_to(16);
+        self.buffer.extend_from_slice(&value.x.to_ne_bytes());
+        self.buffer.extend_from_slice(&value.y.to_ne_bytes());
+        self.buffer.extend_from_slice(&value.z.to_ne_bytes());
+        self.buffer.extend_from_slice(&value.w.to_ne_bytes());
+        self
+    }
+
+    /// A `mat4` is four column `vec4`s, each aligned to 16 bytes.
+    pub fn write_mat4(&mut self, value: Mat4) -> &mut Self {
+        for column in value.f.chunks_exact(4) {
+            self.align_to(16);
+            for component in column {
+                self.buffer.extend_from_slice(&component.to_ne_bytes());
+            }
+        }
+        self
+    }
+
+    // Every array element - including scalar ones - is padded out to a
+    // 16-byte stride in std140, regardless of the element's own alignment.
+    pub fn write_integer_array(&mut self, values: &[i32]) -> &mut Self {
+        for value in values {
+            self.align_to(16);
+            self.buffer.extend_from_slice(&value.to_ne_bytes());
+            self.align_to(16);
+        }
+        self
+    }
+
+    pub fn write_float_array(&mut self, values: &[f32]) -> &mut Self {
+        for value in values {
+            self.align_to(16);
+            self.buffer.extend_from_slice(&value.to_ne_bytes());
+            self.align_to(16);
+        }
+        self
+    }
+
+    pub fn write_vec2_array(&mut self, values: &[Vec2]) -> &mut Self {
+        for value in values {
+            self.align_to(16);
+            self.buffer.extend_from_slice(&value.x.to_ne_bytes());
+            self.buffer.extend_from_slice(&value.y.to_ne_bytes());
+            self.align_to(16);
+        }
+        self
+    }
+
+    pub fn write_vec3_array(&mut self, values: &[Vec3]) -> &mut Self {
+        for value in values {
+            self.align_to(16);
+            self.buffer.extend_from_slice(&value.x.to_ne_bytes());
+            self.buffer.extend_from_slice(&value.y.to_ne_bytes());
+            self.buffer.extend_from_slice(&value.z.to_ne_bytes());
+            self.align_to(16);
+        }
+        self
+    }
+
+    pub fn write_vec4_array(&mut self, values: &[Vec4]) -> &mut Self {
+        for value in values {
+            self.align_to(16);
+            self.buffer.extend_from_slice(&value.x.to_ne_bytes());
+            self.buffer.extend_from_slice(&value.y.to_ne_bytes());
+            self.buffer.extend_from_slice(&value.z.to_ne_bytes());
+            self.buffer.extend_from_slice(&value.w.to_ne_bytes());
+        }
+        self
+    }
+
+    pub fn write_mat4_array(&mut self, values: &[Mat4]) -> &mut Self {
+        for value in values {
+            self.write_mat4(*value);
+        }
+        self
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ShaderVersion {
+    /// Desktop OpenGL 3.3 core profile.
+    Glsl330Core,
+    /// Desktop OpenGL 4.0 core profile, the floor for tessellation.
+    Glsl400Core,
+    /// Desktop OpenGL 4.3 core profile, the floor for compute.
+    Glsl430Core,
+    /// OpenGL ES 2.0 / WebGL 1.0.
+    Gles2,
+    /// OpenGL ES 3.0 / WebGL 2.0.
+    Gles3,
+}
+
+impl ShaderVersion {
+    fn version_directive(self) -> &'static str {
+        match self {
+            ShaderVersion::Glsl330Core => "#version 330 core\n",
+            ShaderVersion::Glsl400Core => "#version 400 core\n",
+            ShaderVersion::Glsl430Core => "#version 430 core\n",
+            ShaderVersion::Gles2 => "#version 100\n#define GLES2_RENDERER\n",
+            ShaderVersion::Gles3 => "#version 300 es\n",
+        }
+    }
+
+    fn is_gles(self) -> bool {
+        matches!(self, ShaderVersion::Gles2 | ShaderVersion::Gles3)
+    }
+
+    // Bumps self up to `required` if it's higher; never downgrades, and
+    // leaves GLES versions alone.
+    fn at_least(self, required: ShaderVersion) -> ShaderVersion {
+        fn rank(version: ShaderVersion) -> u32 {
+            match version {
+                ShaderVersion::Glsl330Core => 330,
+                ShaderVersion::Glsl400Core => 400,
+                ShaderVersion::Glsl430Core => 430,
+                ShaderVersion::Gles2 | ShaderVersion::Gles3 => 0,
+            }
+        }
+
+        if self.is_gles() || rank(required) <= rank(self) {
+            self
+        } else {
+            required
+        }
+    }
+}
+
 #[allow(dead_code)]
 pub enum UniformValue<'a> {
     Sampler {
@@ -57,11 +341,74 @@ pub enum UniformValue<'a> {
     Mat4Array(&'a [Mat4]),
 }
 
-fn create_shader(name: String, actual_type: GLuint, source: &str) -> Result<GLuint, RendererError> {
+/// A shader stage to compile and attach via [`GpuProgram::from_stages`].
+pub struct ShaderStage<'a> {
+    pub kind: StageType,
+    pub source: &'a str,
+}
+
+impl<'a> ShaderStage<'a> {
+    pub fn new(kind: StageType, source: &'a str) -> Self {
+        Self { kind, source }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum StageType {
+    Vertex,
+    Fragment,
+    Geometry,
+    TessControl,
+    TessEvaluation,
+    Compute,
+}
+
+impl StageType {
+    fn gl_type(self) -> GLuint {
+        match self {
+            StageType::Vertex => gl::VERTEX_SHADER,
+            StageType::Fragment => gl::FRAGMENT_SHADER,
+            StageType::Geometry => gl::GEOMETRY_SHADER,
+            StageType::TessControl => gl::TESS_CONTROL_SHADER,
+            StageType::TessEvaluation => gl::TESS_EVALUATION_SHADER,
+            StageType::Compute => gl::COMPUTE_SHADER,
+        }
+    }
+
+    fn name_suffix(self) -> &'static str {
+        match self {
+            StageType::Vertex => "VertexShader",
+            StageType::Fragment => "FragmentShader",
+            StageType::Geometry => "GeometryShader",
+            StageType::TessControl => "TessControlShader",
+            StageType::TessEvaluation => "TessEvaluationShader",
+            StageType::Compute => "ComputeShader",
+        }
+    }
+
+    // Lowest desktop GLSL version this stage is legal under.
+    fn min_desktop_version(self) -> ShaderVersion {
+        match self {
+            StageType::Vertex | StageType::Fragment | StageType::Geometry => {
+                ShaderVersion::Glsl330Core
+            }
+            StageType::TessControl | StageType::TessEvaluation => ShaderVersion::Glsl400Core,
+            StageType::Compute => ShaderVersion::Glsl430Core,
+        }
+    }
+}
+
+fn create_shader(
+    name: String,
+    kind: StageType,
+    source: &str,
+    version: ShaderVersion,
+) -> Result<GLuint, RendererError> {
     unsafe {
-        let csource = prepare_source_code(source)?;
+        let is_fragment = kind == StageType::Fragment;
+        let csource = prepare_source_code(source, version, is_fragment)?;
 
-        let shader = gl::CreateShader(actual_type);
+        let shader = gl::CreateShader(kind.gl_type());
         gl::ShaderSource(shader, 1, &csource.as_ptr(), std::ptr::null());
         gl::CompileShader(shader);
 
@@ -94,19 +441,129 @@ fn create_shader(name: String, actual_type: GLuint, source: &str) -> Result<GLui
     }
 }
 
-fn prepare_source_code(code: &str) -> Result<CString, RendererError> {
-    let mut shared = "\n// include 'shared.glsl'\n".to_owned();
-    shared += include_str!("../shaders/shared.glsl");
-    shared += "\n// end of include\n";
-
-    if let Some(p) = code.rfind('#') {
-        let mut full = code.to_owned();
-        let end = p + full[p..].find('\n').unwrap() + 1;
-        full.insert_str(end, &shared);
-        Ok(CString::new(full)?)
+// Strips a pre-existing `#version` line so we don't emit two - the
+// caller-requested `ShaderVersion` always wins.
+fn strip_version_directive(code: &str) -> &str {
+    let trimmed = code.trim_start();
+    if trimmed.starts_with("#version") {
+        match trimmed.find('\n') {
+            Some(end) => &trimmed[end + 1..],
+            // No newline after the directive - can't safely splice it out.
+            None => code,
+        }
     } else {
-        shared += code;
-        Ok(CString::new(shared)?)
+        code
+    }
+}
+
+fn prepare_source_code(
+    code: &str,
+    version: ShaderVersion,
+    is_fragment: bool,
+) -> Result<CString, RendererError> {
+    let code = strip_version_directive(code);
+
+    let mut full = version.version_directive().to_owned();
+    if is_fragment && version.is_gles() {
+        full += "precision highp float;\n";
+    }
+
+    full += "\n// include 'shared.glsl'\n";
+    full += include_str!("../shaders/shared.glsl");
+    full += "\n// end of include\n";
+
+    full += code;
+
+    Ok(CString::new(full)?)
+}
+
+// Enumerates the program's active uniforms right after linking. Uniforms the
+// compiler optimized away simply won't appear here.
+fn collect_active_uniforms(
+    program: GLuint,
+) -> (HashMap<Rc<str>, UniformInfo>, HashMap<GLint, Rc<str>>) {
+    unsafe {
+        let mut count = 0;
+        gl::GetProgramiv(program, gl::ACTIVE_UNIFORMS, &mut count);
+
+        let mut max_name_len = 0;
+        gl::GetProgramiv(program, gl::ACTIVE_UNIFORM_MAX_LENGTH, &mut max_name_len);
+
+        let mut name_buf: Vec<u8> = vec![0; max_name_len.max(1) as usize];
+        let mut uniforms = HashMap::with_capacity(count as usize);
+        let mut names = HashMap::with_capacity(count as usize);
+
+        for index in 0..count as GLuint {
+            let mut length = 0;
+            let mut size = 0;
+            let mut gl_type = 0;
+            gl::GetActiveUniform(
+                program,
+                index,
+                name_buf.len() as i32,
+                &mut length,
+                &mut size,
+                &mut gl_type,
+                name_buf.as_mut_ptr() as *mut i8,
+            );
+
+            // The driver reports array uniforms as e.g. "bones[0]" - strip the
+            // index suffix so `uniforms.get("bones")` actually hits the cache.
+            let mut name = String::from_utf8_lossy(&name_buf[..length as usize]).into_owned();
+            if let Some(bracket) = name.find('[') {
+                name.truncate(bracket);
+            }
+            let location = gl::GetUniformLocation(program, name_buf.as_ptr() as *const i8);
+            let name: Rc<str> = Rc::from(name);
+
+            names.insert(location, name.clone());
+            uniforms.insert(
+                name,
+                UniformInfo {
+                    location,
+                    gl_type,
+                    size,
+                },
+            );
+        }
+
+        (uniforms, names)
+    }
+}
+
+// Whether a `UniformValue` variant is the kind of data a uniform declared
+// with the given GL type actually expects, catching things like setting a
+// `Vec3` on a uniform declared `mat4`.
+fn value_matches_uniform_type(value: &UniformValue, gl_type: GLuint) -> bool {
+    match value {
+        UniformValue::Sampler { .. } => matches!(
+            gl_type,
+            gl::SAMPLER_2D | gl::SAMPLER_CUBE | gl::SAMPLER_2D_ARRAY
+        ),
+        UniformValue::Bool(_) => gl_type == gl::BOOL,
+        UniformValue::Integer(_) | UniformValue::IntegerArray(_) => gl_type == gl::INT,
+        UniformValue::Float(_) | UniformValue::FloatArray(_) => gl_type == gl::FLOAT,
+        UniformValue::Vec2(_) | UniformValue::Vec2Array(_) => gl_type == gl::FLOAT_VEC2,
+        UniformValue::Vec3(_) | UniformValue::Vec3Array(_) => gl_type == gl::FLOAT_VEC3,
+        UniformValue::Vec4(_) | UniformValue::Vec4Array(_) | UniformValue::Color(_) => {
+            gl_type == gl::FLOAT_VEC4
+        }
+        UniformValue::Mat3(_) => gl_type == gl::FLOAT_MAT3,
+        UniformValue::Mat4(_) | UniformValue::Mat4Array(_) => gl_type == gl::FLOAT_MAT4,
+    }
+}
+
+// The element count for array-valued uniforms, used to flag a value that
+// won't fit in the array the shader actually declared.
+fn value_array_len(value: &UniformValue) -> Option<usize> {
+    match value {
+        UniformValue::IntegerArray(v) => Some(v.len()),
+        UniformValue::FloatArray(v) => Some(v.len()),
+        UniformValue::Vec2Array(v) => Some(v.len()),
+        UniformValue::Vec3Array(v) => Some(v.len()),
+        UniformValue::Vec4Array(v) => Some(v.len()),
+        UniformValue::Mat4Array(v) => Some(v.len()),
+        _ => None,
     }
 }
 
@@ -115,23 +572,49 @@ impl GpuProgram {
         name: &str,
         vertex_source: &str,
         fragment_source: &str,
+        version: ShaderVersion,
+    ) -> Result<GpuProgram, RendererError> {
+        Self::from_stages(
+            name,
+            &[
+                ShaderStage::new(StageType::Vertex, vertex_source),
+                ShaderStage::new(StageType::Fragment, fragment_source),
+            ],
+            version,
+        )
+    }
+
+    /// Links a program out of an arbitrary set of shader stages, e.g. a
+    /// vertex/geometry/tessellation pipeline or a standalone compute stage.
+    pub fn from_stages(
+        name: &str,
+        stages: &[ShaderStage],
+        version: ShaderVersion,
     ) -> Result<GpuProgram, RendererError> {
+        let version = stages
+            .iter()
+            .fold(version, |version, stage| version.at_least(stage.kind.min_desktop_version()));
+
         unsafe {
-            let vertex_shader = create_shader(
-                format!("{}_VertexShader", name),
-                gl::VERTEX_SHADER,
-                vertex_source,
-            )?;
-            let fragment_shader = create_shader(
-                format!("{}_FragmentShader", name),
-                gl::FRAGMENT_SHADER,
-                fragment_source,
-            )?;
             let program: GLuint = gl::CreateProgram();
-            gl::AttachShader(program, vertex_shader);
-            gl::DeleteShader(vertex_shader);
-            gl::AttachShader(program, fragment_shader);
-            gl::DeleteShader(fragment_shader);
+
+            for stage in stages {
+                let shader = match create_shader(
+                    format!("{}_{}", name, stage.kind.name_suffix()),
+                    stage.kind,
+                    stage.source,
+                    version,
+                ) {
+                    Ok(shader) => shader,
+                    Err(error) => {
+                        gl::DeleteProgram(program);
+                        return Err(error);
+                    }
+                };
+                gl::AttachShader(program, shader);
+                gl::DeleteShader(shader);
+            }
+
             gl::LinkProgram(program);
             let mut status = 1;
             gl::GetProgramiv(program, gl::LINK_STATUS, &mut status);
@@ -145,32 +628,72 @@ impl GpuProgram {
                     std::ptr::null_mut(),
                     buffer.as_mut_ptr() as *mut i8,
                 );
+                gl::DeleteProgram(program);
                 Err(RendererError::ShaderLinkingFailed {
                     shader_name: name.to_owned(),
                     error_message: String::from_utf8_unchecked(buffer),
                 })
             } else {
+                let (uniforms, uniform_names) = collect_active_uniforms(program);
                 Ok(Self {
+                    uniforms,
+                    uniform_names,
                     id: program,
+                    generation: next_generation(),
                     name_buf: Default::default(),
+                    warned_uniforms: Default::default(),
                     thread_mark: PhantomData,
                 })
             }
         }
     }
 
+    /// Looks up a uniform by name from the cache recorded at link time. A
+    /// uniform the compiler optimized away still resolves, to a handle that
+    /// makes [`set_uniform`](Self::set_uniform) silently do nothing.
     pub fn uniform_location(&self, name: &str) -> Result<UniformLocation, RendererError> {
+        match self.uniforms.get(name) {
+            Some(info) => Ok(UniformLocation {
+                id: info.location,
+                gl_type: info.gl_type,
+                size: info.size,
+                generation: self.generation,
+                thread_mark: PhantomData,
+            }),
+            None => Ok(UniformLocation {
+                id: -1,
+                gl_type: 0,
+                size: 0,
+                generation: self.generation,
+                thread_mark: PhantomData,
+            }),
+        }
+    }
+
+    fn warn_once(&self, key: (u64, GLint), message: String) {
+        if self.warned_uniforms.borrow_mut().insert(key) {
+            Log::writeln(message);
+        }
+    }
+
+    fn uniform_name(&self, location: GLint) -> &str {
+        self.uniform_names
+            .get(&location)
+            .map_or("<unknown>", |name| name.as_ref())
+    }
+
+    pub fn uniform_block_index(&self, name: &str) -> Result<UniformBlockIndex, RendererError> {
         // Form c string in special buffer to reduce memory allocations
         let buf = &mut self.name_buf.borrow_mut();
         buf.clear();
         buf.extend_from_slice(name.as_bytes());
         buf.push(0);
         unsafe {
-            let id = gl::GetUniformLocation(self.id, buf.as_ptr() as *const i8);
-            if id < 0 {
+            let id = gl::GetUniformBlockIndex(self.id, buf.as_ptr() as *const i8);
+            if id == gl::INVALID_INDEX {
                 Err(RendererError::UnableToFindShaderUniform(name.to_owned()))
             } else {
-                Ok(UniformLocation {
+                Ok(UniformBlockIndex {
                     id,
                     thread_mark: PhantomData,
                 })
@@ -178,16 +701,74 @@ impl GpuProgram {
         }
     }
 
+    /// Wires a uniform block declared in this program to `binding_point`.
+    pub fn bind_uniform_block(&self, index: UniformBlockIndex, binding_point: u32) {
+        unsafe {
+            gl::UniformBlockBinding(self.id, index.id, binding_point);
+        }
+    }
+
     pub fn bind(&self, state: &mut State) {
         state.set_program(self.id);
     }
 
+    /// Dispatches `x * y * z` work groups of a compute program.
+    pub fn dispatch(&self, state: &mut State, x: u32, y: u32, z: u32) {
+        state.set_program(self.id);
+        unsafe {
+            gl::DispatchCompute(x, y, z);
+        }
+    }
+
     pub fn set_uniform(
         &self,
         state: &mut State,
         location: UniformLocation,
         value: &UniformValue<'_>,
     ) {
+        // `location` predates a hot-reload swap of this program; re-resolve
+        // it with `uniform_location` instead of applying it to whatever
+        // uniform now occupies that index.
+        if location.generation != self.generation {
+            self.warn_once(
+                (location.generation, location.id),
+                format!(
+                    "Uniform location {} is stale (its program was hot-reloaded since it was resolved); ignoring the set_uniform call.",
+                    location.id
+                ),
+            );
+            return;
+        }
+
+        // Optimized out by the driver - nothing to upload or warn about.
+        if location.id < 0 {
+            return;
+        }
+
+        if !value_matches_uniform_type(value, location.gl_type) {
+            self.warn_once(
+                (self.generation, location.id),
+                format!(
+                    "Uniform {} is declared with a different GLSL type than the value being set for it; ignoring the set_uniform call.",
+                    self.uniform_name(location.id)
+                ),
+            );
+            return;
+        }
+
+        if let Some(len) = value_array_len(value) {
+            if len > location.size as usize {
+                self.warn_once(
+                    (self.generation, location.id),
+                    format!(
+                        "Uniform {} array is declared with {} element(s), but {} were provided; ignoring the set_uniform call.",
+                        self.uniform_name(location.id), location.size, len
+                    ),
+                );
+                return;
+            }
+        }
+
         state.set_program(self.id);
 
         let location = location.id;
@@ -260,3 +841,92 @@ impl Drop for GpuProgram {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn std140_writer_packs_mixed_struct_members_at_expected_offsets() {
+        let bytes = Std140Writer::new()
+            .write_float(1.0)
+            .write_vec3(Vec3 {
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
+            })
+            .write_vec4(Vec4 {
+                x: 4.0,
+                y: 5.0,
+                z: 6.0,
+                w: 7.0,
+            })
+            .write_mat4(Mat4 { f: [0.0; 16] })
+            .finish();
+
+        // float occupies bytes 0-4, vec3 rounds up to the next 16-byte
+        // boundary at 16, vec4 follows immediately at 32, and mat4 is
+        // already 16-byte aligned at 48 and spans four vec4 columns.
+        assert_eq!(bytes.len(), 48 + 64);
+    }
+
+    #[test]
+    fn std140_writer_pads_array_elements_to_a_16_byte_stride() {
+        let bytes = Std140Writer::new().write_float_array(&[1.0, 2.0, 3.0]).finish();
+
+        assert_eq!(bytes.len(), 3 * 16);
+    }
+
+    #[test]
+    fn strip_version_directive_leaves_code_without_one_untouched() {
+        let code = "void main() {}\n";
+        assert_eq!(strip_version_directive(code), code);
+    }
+
+    #[test]
+    fn strip_version_directive_removes_a_version_line_and_its_newline() {
+        let code = "#version 330 core\nvoid main() {}\n";
+        assert_eq!(strip_version_directive(code), "void main() {}\n");
+    }
+
+    #[test]
+    fn strip_version_directive_keeps_the_body_when_the_directive_has_no_trailing_newline() {
+        // A `#version` line with nothing after it (no EOF newline) used to
+        // make this function return "", dropping the entire shader body.
+        let code = "#version 330 core";
+        assert_eq!(strip_version_directive(code), code);
+    }
+
+    fn source_for(version: ShaderVersion, body: &str) -> String {
+        prepare_source_code(body, version, false)
+            .unwrap()
+            .into_string()
+            .unwrap()
+    }
+
+    #[test]
+    fn prepare_source_code_injects_the_requested_version_directive() {
+        for version in [
+            ShaderVersion::Glsl330Core,
+            ShaderVersion::Gles2,
+            ShaderVersion::Gles3,
+        ] {
+            let source = source_for(version, "void main() {}\n");
+            assert!(source.starts_with(version.version_directive()));
+            assert!(source.contains("void main() {}\n"));
+        }
+    }
+
+    #[test]
+    fn prepare_source_code_strips_a_pre_existing_version_directive() {
+        for version in [
+            ShaderVersion::Glsl330Core,
+            ShaderVersion::Gles2,
+            ShaderVersion::Gles3,
+        ] {
+            let source = source_for(version, "#version 150\nvoid main() {}\n");
+            assert!(source.starts_with(version.version_directive()));
+            assert_eq!(source.matches("#version").count(), 1);
+        }
+    }
+}
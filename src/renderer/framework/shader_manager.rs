@@ -0,0 +1,128 @@
+use crate::{
+    renderer::{
+        error::RendererError,
+        framework::gpu_program::{GpuProgram, ShaderVersion},
+    },
+    utils::log::Log,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+struct WatchedShader {
+    program: GpuProgram,
+    vertex_path: PathBuf,
+    fragment_path: PathBuf,
+    vertex_modified: SystemTime,
+    fragment_modified: SystemTime,
+    version: ShaderVersion,
+}
+
+/// Owns shader programs loaded from disk and hot-reloads them on [`ShaderManager::poll`].
+#[derive(Default)]
+pub struct ShaderManager {
+    shaders: HashMap<String, WatchedShader>,
+}
+
+impl ShaderManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_files<P: AsRef<Path>>(
+        &mut self,
+        name: &str,
+        vertex_path: P,
+        fragment_path: P,
+        version: ShaderVersion,
+    ) -> Result<(), RendererError> {
+        let vertex_path = vertex_path.as_ref().to_path_buf();
+        let fragment_path = fragment_path.as_ref().to_path_buf();
+
+        let (vertex_source, fragment_source) = read_sources(&vertex_path, &fragment_path, name)?;
+        let program = GpuProgram::from_source(name, &vertex_source, &fragment_source, version)?;
+
+        self.shaders.insert(
+            name.to_owned(),
+            WatchedShader {
+                program,
+                vertex_modified: modified(&vertex_path),
+                fragment_modified: modified(&fragment_path),
+                vertex_path,
+                fragment_path,
+                version,
+            },
+        );
+
+        Ok(())
+    }
+
+    pub fn program(&self, name: &str) -> Option<&GpuProgram> {
+        self.shaders.get(name).map(|watched| &watched.program)
+    }
+
+    /// Recompiles any watched shader whose source files changed since the
+    /// last call, keeping the last-good program bound if relinking fails.
+    /// Swapping in a new program bumps its generation, so a `UniformLocation`
+    /// cached before the reload is rejected by `set_uniform` and must be
+    /// re-resolved via `uniform_location`.
+    pub fn poll(&mut self) {
+        for (name, shader) in self.shaders.iter_mut() {
+            let vertex_modified = modified(&shader.vertex_path);
+            let fragment_modified = modified(&shader.fragment_path);
+
+            if vertex_modified <= shader.vertex_modified
+                && fragment_modified <= shader.fragment_modified
+            {
+                continue;
+            }
+
+            match read_sources(&shader.vertex_path, &shader.fragment_path, name)
+                .and_then(|(vertex_source, fragment_source)| {
+                    GpuProgram::from_source(name, &vertex_source, &fragment_source, shader.version)
+                }) {
+                Ok(new_program) => {
+                    shader.program = new_program;
+                    shader.vertex_modified = vertex_modified;
+                    shader.fragment_modified = fragment_modified;
+                    Log::writeln(format!("Shader {} hot-reloaded!", name));
+                }
+                Err(error) => {
+                    Log::writeln(format!(
+                        "Failed to hot-reload shader {}: {:?}, keeping last-good version.",
+                        name, error
+                    ));
+                }
+            }
+        }
+    }
+}
+
+fn read_sources(
+    vertex_path: &Path,
+    fragment_path: &Path,
+    name: &str,
+) -> Result<(String, String), RendererError> {
+    let vertex_source = fs::read_to_string(vertex_path).map_err(|error| {
+        RendererError::ShaderCompilationFailed {
+            shader_name: name.to_owned(),
+            error_message: format!("unable to read {}: {}", vertex_path.display(), error),
+        }
+    })?;
+    let fragment_source = fs::read_to_string(fragment_path).map_err(|error| {
+        RendererError::ShaderCompilationFailed {
+            shader_name: name.to_owned(),
+            error_message: format!("unable to read {}: {}", fragment_path.display(), error),
+        }
+    })?;
+    Ok((vertex_source, fragment_source))
+}
+
+fn modified(path: &Path) -> SystemTime {
+    fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .unwrap_or(SystemTime::UNIX_EPOCH)
+}